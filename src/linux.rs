@@ -120,27 +120,116 @@ fn cgroups_num_cpus() -> Option<usize> {
 }
 
 fn init_cgroups() {
-    // Should only be called once
-    debug_assert!(CGROUPS_CPUS.load(Ordering::SeqCst) == 0);
-
     // Fails in Miri by default (cannot open files), and Miri does not have parallelism anyway.
     if cfg!(miri) {
         return;
     }
 
-    if let Some(quota) = load_cgroups("/proc/self/cgroup", "/proc/self/mountinfo") {
-        if quota == 0 {
-            return;
-        }
+    update_cgroups_cpus();
+}
+
+/// Re-reads the cgroup CPU limits (CFS quota and cpuset) and stores the
+/// resulting count in `CGROUPS_CPUS`, so that the
+/// next [`cgroups_num_cpus`] call picks up limits that were changed at
+/// runtime (e.g. in-place vertical scaling rewriting `cpu.max` without a
+/// restart).
+///
+/// `cgroups_num_cpus` otherwise computes the cgroup quota exactly once (via
+/// `ONCE`) and caches it forever; this is an opt-in escape hatch for
+/// long-running daemons that want to re-read it themselves, e.g. on a SIGHUP
+/// or a periodic timer. It may be called before, after, or interleaved with
+/// `cgroups_num_cpus`'s own one-time initialization.
+///
+/// Returns the new CPU count, or `None` if neither a cgroup CPU quota nor a
+/// cpuset restriction is in effect (in which case the cache is cleared, so a
+/// limit that was lifted at
+/// runtime is reflected too).
+pub fn refresh_cgroups_num_cpus() -> Option<usize> {
+    if cfg!(miri) {
+        return None;
+    }
 
-        let logical = logical_cpus();
-        let count = ::std::cmp::min(quota, logical);
+    update_cgroups_cpus()
+}
+
+fn update_cgroups_cpus() -> Option<usize> {
+    let quota = load_cgroups("/proc/self/cgroup", "/proc/self/mountinfo").unwrap_or(0);
+
+    let count = if quota == 0 {
+        0
+    } else {
+        ::std::cmp::min(quota, logical_cpus())
+    };
+
+    CGROUPS_CPUS.store(count, Ordering::SeqCst);
 
-        CGROUPS_CPUS.store(count, Ordering::SeqCst);
+    if count > 0 {
+        Some(count)
+    } else {
+        None
     }
 }
 
 fn load_cgroups<P1, P2>(cgroup_proc: P1, mountinfo_proc: P2) -> Option<usize>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let cgroup_proc = cgroup_proc.as_ref();
+    let mountinfo_proc = mountinfo_proc.as_ref();
+
+    // A cpuset restriction is independent of the CFS quota: a container can
+    // be pinned to a subset of CPUs via `--cpuset-cpus` with no `--cpus`
+    // quota at all, and on cgroup v1 the two controllers can even be mounted
+    // separately. Resolve each independently, so failing to resolve one
+    // (e.g. the `cpu` controller not being mounted) doesn't prevent the
+    // other from being consulted. Take whichever is present, or their min if
+    // both are, and only bail out if neither imposes a restriction.
+    let quota = load_cgroups_quota(cgroup_proc, mountinfo_proc);
+    let cpuset_cpus = load_cgroups_cpuset(cgroup_proc, mountinfo_proc);
+
+    match (quota, cpuset_cpus) {
+        (Some(quota), Some(cpuset_cpus)) => Some(::std::cmp::min(quota, cpuset_cpus)),
+        (Some(quota), None) => Some(quota),
+        (None, Some(cpuset_cpus)) => Some(cpuset_cpus),
+        (None, None) => None,
+    }
+}
+
+fn load_cgroups_quota<P1, P2>(cgroup_proc: P1, mountinfo_proc: P2) -> Option<usize>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let subsys = some!(Subsys::load_cpu(cgroup_proc));
+    let mntinfo = some!(MountInfo::load_cpu(mountinfo_proc, subsys.version));
+    let cgroup = some!(Cgroup::translate(mntinfo, subsys));
+    cgroup.effective_cpu_quota()
+}
+
+fn load_cgroups_cpuset<P1, P2>(cgroup_proc: P1, mountinfo_proc: P2) -> Option<usize>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let subsys = some!(Subsys::load_cpuset(cgroup_proc));
+    let mntinfo = some!(MountInfo::load_cpuset(mountinfo_proc, subsys.version));
+    let cgroup = some!(Cgroup::translate(mntinfo, subsys));
+    cgroup.cpuset_cpus()
+}
+
+/// The unrounded cgroup CPU quota, e.g. `1.5` for a container limited to
+/// `150000/100000`. Like `cgroups_num_cpus`'s own integer quota, this walks
+/// the hierarchy up to the cgroup mount point and returns the tightest
+/// ancestor quota, not just the leaf cgroup's own. Unlike `cgroups_num_cpus`,
+/// this is not cached and reflects the quota at the time of the call.
+///
+/// Returns `None` if there is no cgroup CPU quota in effect (or this isn't Linux).
+pub fn get_num_cpus_quota_f64() -> Option<f64> {
+    load_cgroups_quota_f64("/proc/self/cgroup", "/proc/self/mountinfo")
+}
+
+fn load_cgroups_quota_f64<P1, P2>(cgroup_proc: P1, mountinfo_proc: P2) -> Option<f64>
 where
     P1: AsRef<Path>,
     P2: AsRef<Path>,
@@ -148,7 +237,7 @@ where
     let subsys = some!(Subsys::load_cpu(cgroup_proc));
     let mntinfo = some!(MountInfo::load_cpu(mountinfo_proc, subsys.version));
     let cgroup = some!(Cgroup::translate(mntinfo, subsys));
-    cgroup.cpu_quota()
+    cgroup.effective_cpu_quota_f64()
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -159,6 +248,9 @@ enum CgroupVersion {
 
 struct Cgroup {
     version: CgroupVersion,
+    // Cgroup mount point, e.g. `/sys/fs/cgroup/cpu`. Bounds how far up the
+    // hierarchy `effective_cpu_quota` is allowed to walk from `base`.
+    mount_point: PathBuf,
     base: PathBuf,
 }
 
@@ -174,8 +266,8 @@ struct Subsys {
 }
 
 impl Cgroup {
-    fn new(version: CgroupVersion, dir: PathBuf) -> Cgroup {
-        Cgroup { version: version, base: dir }
+    fn new(version: CgroupVersion, mount_point: PathBuf, dir: PathBuf) -> Cgroup {
+        Cgroup { version: version, mount_point: mount_point, base: dir }
     }
 
     fn translate(mntinfo: MountInfo, subsys: Subsys) -> Option<Cgroup> {
@@ -190,12 +282,16 @@ impl Cgroup {
         debug!("rel_from_root: {:?}", rel_from_root);
 
         // join(mp.MountPoint, relPath)
-        let mut path = PathBuf::from(mntinfo.mount_point);
+        let mount_point = PathBuf::from(mntinfo.mount_point);
+        let mut path = mount_point.clone();
         path.push(rel_from_root);
-        Some(Cgroup::new(mntinfo.version, path))
+        Some(Cgroup::new(mntinfo.version, mount_point, path))
     }
 
-    fn cpu_quota(&self) -> Option<usize> {
+    /// The unrounded CPU quota, e.g. `1.5` for a container limited to
+    /// `150000/100000`. Schedulers that size work by fractional CPU share
+    /// want this instead of the integer, ceiling-rounded count.
+    fn cpu_quota_f64(&self) -> Option<f64> {
         let (quota_us, period_us) = match self.version {
             CgroupVersion::V1 => (some!(self.quota_us()), some!(self.period_us())),
             CgroupVersion::V2 => some!(self.max()),
@@ -206,10 +302,43 @@ impl Cgroup {
             return None;
         }
 
-        // Ceil the division, since we want to be able to saturate
-        // the available CPUs, and flooring would leave a CPU un-utilized.
+        Some(quota_us as f64 / period_us as f64)
+    }
 
-        Some((quota_us as f64 / period_us as f64).ceil() as usize)
+    /// The tightest CPU quota enforced anywhere from `base` up to the cgroup
+    /// mount point, since the kernel applies whichever ancestor's limit is
+    /// strictest rather than only the leaf cgroup's own quota.
+    fn effective_cpu_quota(&self) -> Option<usize> {
+        self.effective_cpu_quota_f64().map(|quota| quota.ceil() as usize)
+    }
+
+    /// Same as [`Cgroup::effective_cpu_quota`], but unrounded.
+    fn effective_cpu_quota_f64(&self) -> Option<f64> {
+        // `Path::ancestors` was only stabilized in Rust 1.28, so walk the
+        // hierarchy by hand via `Path::parent` (stable since 1.0) instead,
+        // to keep supporting Rust 1.13.
+        let mut tightest = None;
+        let mut dir = Some(self.base.as_path());
+
+        while let Some(current) = dir {
+            if !current.starts_with(&self.mount_point) {
+                break;
+            }
+
+            if let Some(quota) =
+                Cgroup::new(self.version, self.mount_point.clone(), current.to_path_buf())
+                    .cpu_quota_f64()
+            {
+                tightest = Some(match tightest {
+                    Some(tightest) => f64::min(tightest, quota),
+                    None => quota,
+                });
+            }
+
+            dir = current.parent();
+        }
+
+        tightest
     }
 
     fn quota_us(&self) -> Option<usize> {
@@ -236,6 +365,50 @@ impl Cgroup {
         buf.trim().parse().ok()
     }
 
+    /// Number of CPUs selected by the cpuset controller, or `None` if the
+    /// cgroup imposes no cpuset restriction (the file is absent or empty).
+    fn cpuset_cpus(&self) -> Option<usize> {
+        let param = match self.version {
+            CgroupVersion::V1 => "cpuset.cpus",
+            // The effective file reflects the set actually enforced by the
+            // kernel after masking with ancestor cgroups.
+            CgroupVersion::V2 => "cpuset.cpus.effective",
+        };
+
+        let raw = some!(self.raw_param(param));
+        Cgroup::parse_cpu_list(raw.trim())
+    }
+
+    /// Parses a cgroup cpuset list such as `0-2,5,7-8` into the number of
+    /// CPUs it selects. An empty list means "no restriction".
+    fn parse_cpu_list(list: &str) -> Option<usize> {
+        if list.is_empty() {
+            return None;
+        }
+
+        let mut count = 0;
+        for part in list.split(',') {
+            let part = part.trim();
+            if part.is_empty() {
+                continue;
+            }
+
+            count += if let Some(dash) = part.find('-') {
+                let lo: usize = some!(part[..dash].parse().ok());
+                let hi: usize = some!(part[dash + 1..].parse().ok());
+                if hi < lo {
+                    return None;
+                }
+                hi - lo + 1
+            } else {
+                let _: usize = some!(part.parse().ok());
+                1
+            };
+        }
+
+        Some(count)
+    }
+
     fn raw_param(&self, param: &str) -> Option<String> {
         let mut file = some!(File::open(self.base.join(param)).ok());
 
@@ -247,17 +420,29 @@ impl Cgroup {
 }
 
 impl MountInfo {
-    fn load_cpu<P: AsRef<Path>>(proc_path: P, version: CgroupVersion) -> Option<MountInfo> {
+    fn load<P: AsRef<Path>>(
+        proc_path: P,
+        version: CgroupVersion,
+        controller: &str,
+    ) -> Option<MountInfo> {
         let file = some!(File::open(proc_path).ok());
         let file = BufReader::new(file);
 
         file.lines()
             .filter_map(|result| result.ok())
-            .filter_map(MountInfo::parse_line)
+            .filter_map(|line| MountInfo::parse_line(line, controller))
             .find(|mount_info| mount_info.version == version)
     }
 
-    fn parse_line(line: String) -> Option<MountInfo> {
+    fn load_cpu<P: AsRef<Path>>(proc_path: P, version: CgroupVersion) -> Option<MountInfo> {
+        MountInfo::load(proc_path, version, "cpu")
+    }
+
+    fn load_cpuset<P: AsRef<Path>>(proc_path: P, version: CgroupVersion) -> Option<MountInfo> {
+        MountInfo::load(proc_path, version, "cpuset")
+    }
+
+    fn parse_line(line: String, controller: &str) -> Option<MountInfo> {
         let mut fields = line.split(' ');
 
         // 7 5 0:6 </> /sys/fs/cgroup/cpu,cpuacct rw,nosuid,nodev,noexec,relatime shared:7 - cgroup cgroup rw,cpu,cpuacct
@@ -287,8 +472,8 @@ impl MountInfo {
             // 7 5 0:6 / /sys/fs/cgroup/cpu,cpuacct rw,nosuid,nodev,noexec,relatime shared:7 - cgroup cgroup <rw,cpu,cpuacct>
             let super_opts = some!(fields.nth(1));
 
-            // We only care about the 'cpu' option
-            if !super_opts.split(',').any(|opt| opt == "cpu") {
+            // We only care about the requested controller's option
+            if !super_opts.split(',').any(|opt| opt == controller) {
                 return None;
             }
         }
@@ -302,13 +487,13 @@ impl MountInfo {
 }
 
 impl Subsys {
-    fn load_cpu<P: AsRef<Path>>(proc_path: P) -> Option<Subsys> {
+    fn load<P: AsRef<Path>>(proc_path: P, controller: &str) -> Option<Subsys> {
         let file = some!(File::open(proc_path).ok());
         let file = BufReader::new(file);
 
         file.lines()
             .filter_map(|result| result.ok())
-            .filter_map(Subsys::parse_line)
+            .filter_map(|line| Subsys::parse_line(line, controller))
             .fold(None, |previous, line| {
                 // already-found v1 trumps v2 since it explicitly specifies its controllers
                 if previous.is_some() && line.version == CgroupVersion::V2 {
@@ -319,7 +504,15 @@ impl Subsys {
             })
     }
 
-    fn parse_line(line: String) -> Option<Subsys> {
+    fn load_cpu<P: AsRef<Path>>(proc_path: P) -> Option<Subsys> {
+        Subsys::load(proc_path, "cpu")
+    }
+
+    fn load_cpuset<P: AsRef<Path>>(proc_path: P) -> Option<Subsys> {
+        Subsys::load(proc_path, "cpuset")
+    }
+
+    fn parse_line(line: String, controller: &str) -> Option<Subsys> {
         // Example format:
         // 11:cpu,cpuacct:/
         let mut fields = line.split(':');
@@ -332,7 +525,7 @@ impl Subsys {
             CgroupVersion::V1
         };
 
-        if version == CgroupVersion::V1 && !sub_systems.split(',').any(|sub| sub == "cpu") {
+        if version == CgroupVersion::V1 && !sub_systems.split(',').any(|sub| sub == controller) {
             return None;
         }
 
@@ -451,22 +644,84 @@ mod tests {
 
         #[test]
         fn test_cgroup_cpu_quota() {
-            let cgroup = Cgroup::new(CgroupVersion::V1, join!(FIXTURES_CGROUPS, "good"));
-            assert_eq!(cgroup.cpu_quota(), Some(6));
+            let mount_point = join!(FIXTURES_CGROUPS, "good");
+
+            let cgroup = Cgroup::new(CgroupVersion::V1, mount_point.clone(), mount_point);
+            assert_eq!(cgroup.effective_cpu_quota(), Some(6));
         }
 
         #[test]
         fn test_cgroup_cpu_quota_divide_by_zero() {
-            let cgroup = Cgroup::new(CgroupVersion::V1, join!(FIXTURES_CGROUPS, "zero-period"));
+            let mount_point = join!(FIXTURES_CGROUPS, "zero-period");
+
+            let cgroup = Cgroup::new(CgroupVersion::V1, mount_point.clone(), mount_point);
             assert!(cgroup.quota_us().is_some());
             assert_eq!(cgroup.period_us(), Some(0));
-            assert_eq!(cgroup.cpu_quota(), None);
+            assert_eq!(cgroup.effective_cpu_quota(), None);
         }
 
         #[test]
         fn test_cgroup_cpu_quota_ceil() {
-            let cgroup = Cgroup::new(CgroupVersion::V1, join!(FIXTURES_CGROUPS, "ceil"));
-            assert_eq!(cgroup.cpu_quota(), Some(2));
+            let mount_point = join!(FIXTURES_CGROUPS, "ceil");
+
+            let cgroup = Cgroup::new(CgroupVersion::V1, mount_point.clone(), mount_point);
+            assert_eq!(cgroup.effective_cpu_quota(), Some(2));
+        }
+
+        #[test]
+        fn test_cgroup_cpu_quota_f64() {
+            let mount_point = join!(FIXTURES_CGROUPS, "ceil");
+
+            let cgroup = Cgroup::new(CgroupVersion::V1, mount_point.clone(), mount_point);
+            assert_eq!(cgroup.cpu_quota_f64(), Some(1.5));
+        }
+
+        #[test]
+        fn test_cgroup_cpuset_cpus() {
+            // cpuset.cpus contains "0-2,5,7-8": 0,1,2,5,7,8 = 6 CPUs
+            let mount_point = join!(FIXTURES_CGROUPS, "cpuset");
+
+            let cgroup = Cgroup::new(CgroupVersion::V1, mount_point.clone(), mount_point);
+            assert_eq!(cgroup.cpuset_cpus(), Some(6));
+        }
+
+        #[test]
+        fn test_cgroup_cpuset_cpus_unrestricted() {
+            // no cpuset.cpus file present means "no restriction"
+            let mount_point = join!(FIXTURES_CGROUPS, "good");
+
+            let cgroup = Cgroup::new(CgroupVersion::V1, mount_point.clone(), mount_point);
+            assert_eq!(cgroup.cpuset_cpus(), None);
+        }
+
+        #[test]
+        fn test_parse_cpu_list() {
+            assert_eq!(Cgroup::parse_cpu_list("0-2,5,7-8"), Some(6));
+            assert_eq!(Cgroup::parse_cpu_list("0"), Some(1));
+            assert_eq!(Cgroup::parse_cpu_list(""), None);
+            assert_eq!(Cgroup::parse_cpu_list("bogus"), None);
+        }
+
+        #[test]
+        fn test_cgroup_effective_cpu_quota_parent_tighter() {
+            // "nested" caps at 2 CPUs; its "child" is unlimited, so the
+            // tighter ancestor limit should win.
+            let mount_point = PathBuf::from(FIXTURES_CGROUPS);
+            let base = join!(FIXTURES_CGROUPS, "nested", "child");
+            let cgroup = Cgroup::new(CgroupVersion::V1, mount_point, base);
+
+            assert_eq!(cgroup.effective_cpu_quota(), Some(2));
+        }
+
+        #[test]
+        fn test_cgroup_effective_cpu_quota_f64_parent_tighter() {
+            // "nested" caps at 2.0 CPUs; its "child" is unlimited, so the
+            // tighter ancestor limit should win.
+            let mount_point = PathBuf::from(FIXTURES_CGROUPS);
+            let base = join!(FIXTURES_CGROUPS, "nested", "child");
+            let cgroup = Cgroup::new(CgroupVersion::V1, mount_point, base);
+
+            assert_eq!(cgroup.effective_cpu_quota_f64(), Some(2.0));
         }
     }
 
@@ -570,23 +825,77 @@ mod tests {
 
         #[test]
         fn test_cgroup_cpu_quota() {
-            let cgroup = Cgroup::new(CgroupVersion::V2, join!(FIXTURES_CGROUPS, "good"));
-            assert_eq!(cgroup.cpu_quota(), Some(6));
+            let mount_point = join!(FIXTURES_CGROUPS, "good");
+
+            let cgroup = Cgroup::new(CgroupVersion::V2, mount_point.clone(), mount_point);
+            assert_eq!(cgroup.effective_cpu_quota(), Some(6));
         }
 
         #[test]
         fn test_cgroup_cpu_quota_divide_by_zero() {
-            let cgroup = Cgroup::new(CgroupVersion::V2, join!(FIXTURES_CGROUPS, "zero-period"));
+            let mount_point = join!(FIXTURES_CGROUPS, "zero-period");
+
+            let cgroup = Cgroup::new(CgroupVersion::V2, mount_point.clone(), mount_point);
             let period = cgroup.max().map(|max| max.1);
 
             assert_eq!(period, Some(0));
-            assert_eq!(cgroup.cpu_quota(), None);
+            assert_eq!(cgroup.effective_cpu_quota(), None);
         }
 
         #[test]
         fn test_cgroup_cpu_quota_ceil() {
-            let cgroup = Cgroup::new(CgroupVersion::V2, join!(FIXTURES_CGROUPS, "ceil"));
-            assert_eq!(cgroup.cpu_quota(), Some(2));
+            let mount_point = join!(FIXTURES_CGROUPS, "ceil");
+
+            let cgroup = Cgroup::new(CgroupVersion::V2, mount_point.clone(), mount_point);
+            assert_eq!(cgroup.effective_cpu_quota(), Some(2));
+        }
+
+        #[test]
+        fn test_cgroup_cpu_quota_f64() {
+            let mount_point = join!(FIXTURES_CGROUPS, "ceil");
+
+            let cgroup = Cgroup::new(CgroupVersion::V2, mount_point.clone(), mount_point);
+            assert_eq!(cgroup.cpu_quota_f64(), Some(1.5));
+        }
+
+        #[test]
+        fn test_cgroup_cpuset_cpus() {
+            // cpuset.cpus.effective contains "0-2,5,7-8": 0,1,2,5,7,8 = 6 CPUs
+            let mount_point = join!(FIXTURES_CGROUPS, "cpuset");
+
+            let cgroup = Cgroup::new(CgroupVersion::V2, mount_point.clone(), mount_point);
+            assert_eq!(cgroup.cpuset_cpus(), Some(6));
+        }
+
+        #[test]
+        fn test_cgroup_cpuset_cpus_unrestricted() {
+            // no cpuset.cpus.effective file present means "no restriction"
+            let mount_point = join!(FIXTURES_CGROUPS, "good");
+
+            let cgroup = Cgroup::new(CgroupVersion::V2, mount_point.clone(), mount_point);
+            assert_eq!(cgroup.cpuset_cpus(), None);
+        }
+
+        #[test]
+        fn test_cgroup_effective_cpu_quota_parent_tighter() {
+            // "nested" caps at 2 CPUs; its "child" is unlimited, so the
+            // tighter ancestor limit should win.
+            let mount_point = PathBuf::from(FIXTURES_CGROUPS);
+            let base = join!(FIXTURES_CGROUPS, "nested", "child");
+            let cgroup = Cgroup::new(CgroupVersion::V2, mount_point, base);
+
+            assert_eq!(cgroup.effective_cpu_quota(), Some(2));
+        }
+
+        #[test]
+        fn test_cgroup_effective_cpu_quota_f64_parent_tighter() {
+            // "nested" caps at 2.0 CPUs; its "child" is unlimited, so the
+            // tighter ancestor limit should win.
+            let mount_point = PathBuf::from(FIXTURES_CGROUPS);
+            let base = join!(FIXTURES_CGROUPS, "nested", "child");
+            let cgroup = Cgroup::new(CgroupVersion::V2, mount_point, base);
+
+            assert_eq!(cgroup.effective_cpu_quota_f64(), Some(2.0));
         }
     }
 }